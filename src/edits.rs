@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use regex::Regex;
 
 use unicode_segmentation::UnicodeSegmentation;
@@ -8,6 +10,14 @@ use crate::align;
 /// lines. A "line" is a string. An annotated line is a Vec of (op, &str) pairs, where the &str
 /// slices are slices of the line, and their concatenation equals the line. Return the input minus
 /// and plus lines, in annotated form.
+///
+/// Pairing minus lines up with their plus-side homologs (if any) is posed as a global alignment
+/// problem rather than being decided line-by-line: see `align_lines`. `tokenizer` determines the
+/// atomic units that alignment (both for homology distance and for intra-line highlighting)
+/// operates on; see `Tokenizer`.
+///
+/// See `infer_edits_with_spans` for a variant that also locates each section within its line.
+#[allow(clippy::too_many_arguments)]
 pub fn infer_edits<'a, EditOperation>(
     minus_lines: &'a Vec<String>,
     plus_lines: &'a Vec<String>,
@@ -16,6 +26,7 @@ pub fn infer_edits<'a, EditOperation>(
     noop_insertion: EditOperation,
     insertion: EditOperation,
     max_line_distance: f64,
+    tokenizer: &Tokenizer,
 ) -> (
     Vec<Vec<(EditOperation, &'a str)>>, // annotated minus lines
     Vec<Vec<(EditOperation, &'a str)>>, // annotated plus lines
@@ -24,57 +35,410 @@ where
     EditOperation: Copy,
     EditOperation: PartialEq,
 {
-    let mut annotated_minus_lines = Vec::<Vec<(EditOperation, &str)>>::new();
-    let mut annotated_plus_lines = Vec::<Vec<(EditOperation, &str)>>::new();
+    let (minus, plus) = infer_edits_with_spans(
+        minus_lines,
+        plus_lines,
+        noop_deletion,
+        deletion,
+        noop_insertion,
+        insertion,
+        max_line_distance,
+        tokenizer,
+    );
+    let strip_spans = |lines: Vec<Vec<(EditOperation, &'a str, Span)>>| {
+        lines
+            .into_iter()
+            .map(|line| line.into_iter().map(|(op, s, _span)| (op, s)).collect())
+            .collect()
+    };
+    (strip_spans(minus), strip_spans(plus))
+}
+
+/// As `infer_edits`, but additionally returns each section's `Span`: its byte range (and
+/// cumulative grapheme/"column" range) within the original line. This lets a caller map a
+/// highlighted deletion/insertion back to a concrete `(start, end)` in the buffer, as editor and
+/// LSP-style integrations need, rather than having to re-search the string for the section.
+#[allow(clippy::too_many_arguments)]
+pub fn infer_edits_with_spans<'a, EditOperation>(
+    minus_lines: &'a Vec<String>,
+    plus_lines: &'a Vec<String>,
+    noop_deletion: EditOperation,
+    deletion: EditOperation,
+    noop_insertion: EditOperation,
+    insertion: EditOperation,
+    max_line_distance: f64,
+    tokenizer: &Tokenizer,
+) -> (
+    Vec<Vec<(EditOperation, &'a str, Span)>>, // annotated minus lines
+    Vec<Vec<(EditOperation, &'a str, Span)>>, // annotated plus lines
+)
+where
+    EditOperation: Copy,
+    EditOperation: PartialEq,
+{
+    let mut annotated_minus_lines = Vec::<Vec<(EditOperation, &str, Span)>>::new();
+    let mut annotated_plus_lines = Vec::<Vec<(EditOperation, &str, Span)>>::new();
+
+    let whole_line_span = |line: &str| Span {
+        bytes: 0..line.len(),
+        columns: 0..line.graphemes(true).count(),
+    };
+
+    for mv in align_lines(minus_lines, plus_lines, max_line_distance, tokenizer) {
+        match mv {
+            Move::Pair(i, j) => {
+                let (minus_line, plus_line) = (&minus_lines[i], &plus_lines[j]);
+                let alignment =
+                    align::Alignment::new(tokenizer.split(minus_line), tokenizer.split(plus_line));
+                let (annotated_minus_line, annotated_plus_line, _distance) = annotate(
+                    alignment,
+                    noop_deletion,
+                    deletion,
+                    noop_insertion,
+                    insertion,
+                    minus_line,
+                    plus_line,
+                );
+                annotated_minus_lines.push(annotated_minus_line);
+                annotated_plus_lines.push(annotated_plus_line);
+            }
+            Move::DeleteMinus(i) => {
+                let minus_line = minus_lines[i].as_str();
+                annotated_minus_lines.push(vec![(
+                    noop_deletion,
+                    minus_line,
+                    whole_line_span(minus_line),
+                )]);
+            }
+            Move::InsertPlus(j) => {
+                let plus_line = plus_lines[j].as_str();
+                annotated_plus_lines.push(vec![(
+                    noop_insertion,
+                    plus_line,
+                    whole_line_span(plus_line),
+                )]);
+            }
+        }
+    }
+
+    (annotated_minus_lines, annotated_plus_lines)
+}
+
+/// A step in the optimal, order-preserving alignment between minus and plus lines computed by
+/// `align_lines`: either a homologous pairing, or a wholly deleted / wholly inserted line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Move {
+    Pair(usize, usize), // (minus index, plus index)
+    DeleteMinus(usize),
+    InsertPlus(usize),
+}
+
+/// Compute the globally optimal, order-preserving (no-crossing) alignment between `minus_lines`
+/// and `plus_lines`.
+///
+/// Rather than greedily pairing each minus line with the first subsequent plus line whose
+/// `annotate`-computed distance is under `max_line_distance` (which can lock in a mediocre
+/// pairing when a better homolog appears a little later), this runs a Needleman-Wunsch-style
+/// dynamic program over lines: `cost[i][j]` is the homology distance between `minus_lines[i]`
+/// and `plus_lines[j]`, leaving either line unpaired costs 1, and
+/// `d[i][j] = min(d[i-1][j] + 1, d[i][j-1] + 1, d[i-1][j-1] + cost[i][j])` with `cost[i][j]`
+/// treated as infinite above `max_line_distance`. Backtracking `d` then recovers the
+/// minimum-cost sequence of `Move`s.
+fn align_lines(
+    minus_lines: &[String],
+    plus_lines: &[String],
+    max_line_distance: f64,
+    tokenizer: &Tokenizer,
+) -> Vec<Move> {
+    let (n, m) = (minus_lines.len(), plus_lines.len());
+
+    // Each line is tokenized once and the result reused across its whole row/column of `cost`,
+    // rather than re-tokenizing it against every line on the other side.
+    let minus_tokens: Vec<Vec<&str>> = minus_lines
+        .iter()
+        .map(|line| tokenizer.split(line))
+        .collect();
+    let plus_tokens: Vec<Vec<&str>> = plus_lines
+        .iter()
+        .map(|line| tokenizer.split(line))
+        .collect();
+
+    // cost[i][j] is the homology distance between minus_lines[i] and plus_lines[j], as computed
+    // by annotate() (the annotated sections themselves are discarded here and rebuilt by the
+    // caller for the pairs that survive backtracking).
+    // TODO: for long inputs, only a forward band around the diagonal need be computed.
+    let cost: Vec<Vec<f64>> = minus_lines
+        .iter()
+        .zip(&minus_tokens)
+        .map(|(minus_line, minus_tokens)| {
+            plus_lines
+                .iter()
+                .zip(&plus_tokens)
+                .map(|(plus_line, plus_tokens)| {
+                    let alignment =
+                        align::Alignment::new(minus_tokens.clone(), plus_tokens.clone());
+                    let (_, _, distance) =
+                        annotate(alignment, (), (), (), (), minus_line, plus_line);
+                    distance
+                })
+                .collect()
+        })
+        .collect();
+
+    const GAP_COST: f64 = 1.0;
+    let mut d = vec![vec![0.0_f64; m + 1]; n + 1];
+    for i in 1..=n {
+        d[i][0] = d[i - 1][0] + GAP_COST;
+    }
+    for j in 1..=m {
+        d[0][j] = d[0][j - 1] + GAP_COST;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let substitution_cost = if cost[i - 1][j - 1] <= max_line_distance {
+                cost[i - 1][j - 1]
+            } else {
+                f64::INFINITY
+            };
+            d[i][j] = (d[i - 1][j] + GAP_COST)
+                .min(d[i][j - 1] + GAP_COST)
+                .min(d[i - 1][j - 1] + substitution_cost);
+        }
+    }
+
+    // Backtrack from (n, m) to (0, 0), preferring a pairing over leaving either line unpaired
+    // when costs tie.
+    let mut moves = Vec::new();
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 {
+            let substitution_cost = if cost[i - 1][j - 1] <= max_line_distance {
+                cost[i - 1][j - 1]
+            } else {
+                f64::INFINITY
+            };
+            if d[i][j] == d[i - 1][j - 1] + substitution_cost {
+                moves.push(Move::Pair(i - 1, j - 1));
+                i -= 1;
+                j -= 1;
+                continue;
+            }
+        }
+        if i > 0 && d[i][j] == d[i - 1][j] + GAP_COST {
+            moves.push(Move::DeleteMinus(i - 1));
+            i -= 1;
+            continue;
+        }
+        moves.push(Move::InsertPlus(j - 1));
+        j -= 1;
+    }
+    moves.reverse();
+
+    moves
+}
+
+/// Incremental counterpart to `infer_edits`, for callers that receive `plus_lines` one at a time
+/// (e.g. a diff whose new side is streamed in from an external process) and want to redraw
+/// progressively without the annotation already shown for an earlier line flickering once a
+/// later line arrives.
+///
+/// `minus_lines` are known up front; plus lines are fed in via `push_plus_line`. Unlike
+/// `infer_edits`'s global `align_lines` pass, pairing is decided greedily, one minus line at a
+/// time: `minus_lines[next_minus]` is matched against plus lines in arrival order, starting from
+/// the first one not yet `emitted`, and is paired with the first one under `max_line_distance`
+/// (`considered` counts how many were tried and rejected first). This is deliberately the same
+/// trade-off `align_lines` improves on for the batch case, made here because the alternative is
+/// fundamentally incompatible with committing annotations as the lines arrive: finding the
+/// globally optimal pairing for `minus_lines[next_minus]` can require seeing plus lines that
+/// arrive arbitrarily far in the future, which no commitment made before they arrive can account
+/// for. Greedy pairing has the opposite, streaming-friendly property: once a decision is made
+/// (a pairing, or a plus line skipped over on the way to one) it depends only on plus lines
+/// already seen, so it is final and is never revised by a later push. `finalize` treats the
+/// lines pushed so far as complete, resolving whichever minus line was still pending.
+pub struct EditInferrer<'a, EditOperation> {
+    minus_lines: &'a Vec<String>,
+    plus_lines: Vec<String>,
+    noop_deletion: EditOperation,
+    deletion: EditOperation,
+    noop_insertion: EditOperation,
+    insertion: EditOperation,
+    max_line_distance: f64,
+    tokenizer: Tokenizer,
+    next_minus: usize, // index of the minus line currently being matched
+    emitted: usize,    // plus lines already committed, paired or not
+    considered: usize, // plus lines since `emitted` tried against `next_minus` and rejected
+    annotated_minus_lines: Vec<Vec<(EditOperation, String)>>,
+    annotated_plus_lines: Vec<Vec<(EditOperation, String)>>,
+}
+
+impl<'a, EditOperation> EditInferrer<'a, EditOperation>
+where
+    EditOperation: Copy,
+    EditOperation: PartialEq,
+{
+    pub fn new(
+        minus_lines: &'a Vec<String>,
+        noop_deletion: EditOperation,
+        deletion: EditOperation,
+        noop_insertion: EditOperation,
+        insertion: EditOperation,
+        max_line_distance: f64,
+        tokenizer: Tokenizer,
+    ) -> Self {
+        Self {
+            minus_lines,
+            plus_lines: Vec::new(),
+            noop_deletion,
+            deletion,
+            noop_insertion,
+            insertion,
+            max_line_distance,
+            tokenizer,
+            next_minus: 0,
+            emitted: 0,
+            considered: 0,
+            annotated_minus_lines: Vec::new(),
+            annotated_plus_lines: Vec::new(),
+        }
+    }
+
+    /// Feed in the next plus line, and commit any pairings that have become decidable as a
+    /// result.
+    pub fn push_plus_line(&mut self, line: String) {
+        self.plus_lines.push(line);
+        self.advance(false);
+    }
 
-    let mut emitted = 0; // plus lines emitted so far
+    /// Treat the plus lines pushed so far as complete, and return the fully annotated minus and
+    /// plus lines, in the same form `infer_edits` would have produced them.
+    pub fn finalize(
+        mut self,
+    ) -> (
+        Vec<Vec<(EditOperation, String)>>,
+        Vec<Vec<(EditOperation, String)>>,
+    ) {
+        self.advance(true);
+        (self.annotated_minus_lines, self.annotated_plus_lines)
+    }
+
+    /// The minus lines annotated so far, in order; never revised by a later push.
+    pub fn annotated_minus_lines(&self) -> &[Vec<(EditOperation, String)>] {
+        &self.annotated_minus_lines
+    }
 
-    'minus_lines_loop: for minus_line in minus_lines {
-        let mut considered = 0; // plus lines considered so far as match for minus_line
-        for plus_line in &plus_lines[emitted..] {
-            let alignment = align::Alignment::new(tokenize(minus_line), tokenize(plus_line));
+    /// The plus lines annotated so far, in order; never revised by a later push.
+    pub fn annotated_plus_lines(&self) -> &[Vec<(EditOperation, String)>] {
+        &self.annotated_plus_lines
+    }
+
+    // Resolve as many minus lines as the plus lines seen so far allow. If `no_more_plus_lines` is
+    // set, a minus line that has run out of candidates without finding a homolog is committed as
+    // an unpaired deletion (rather than left pending for a push that will never come), exactly as
+    // the final minus lines of a batch `infer_edits` call are.
+    fn advance(&mut self, no_more_plus_lines: bool) {
+        while self.next_minus < self.minus_lines.len() {
+            let candidate = self.emitted + self.considered;
+            if candidate >= self.plus_lines.len() {
+                if !no_more_plus_lines {
+                    return;
+                }
+                self.annotated_minus_lines.push(vec![(
+                    self.noop_deletion,
+                    self.minus_lines[self.next_minus].clone(),
+                )]);
+                self.considered = 0;
+                self.next_minus += 1;
+                continue;
+            }
+            let (minus_line, plus_line) = (
+                &self.minus_lines[self.next_minus],
+                &self.plus_lines[candidate],
+            );
+            let alignment = align::Alignment::new(
+                self.tokenizer.split(minus_line),
+                self.tokenizer.split(plus_line),
+            );
             let (annotated_minus_line, annotated_plus_line, distance) = annotate(
                 alignment,
-                noop_deletion,
-                deletion,
-                noop_insertion,
-                insertion,
+                self.noop_deletion,
+                self.deletion,
+                self.noop_insertion,
+                self.insertion,
                 minus_line,
                 plus_line,
             );
-            if distance <= max_line_distance {
-                // minus_line and plus_line are inferred to be a homologous pair.
-
-                // Emit as unpaired the plus lines already considered and rejected
-                for plus_line in &plus_lines[emitted..(emitted + considered)] {
-                    annotated_plus_lines.push(vec![(noop_insertion, plus_line)]);
+            if distance <= self.max_line_distance {
+                for rejected in self.emitted..candidate {
+                    self.annotated_plus_lines.push(vec![(
+                        self.noop_insertion,
+                        self.plus_lines[rejected].clone(),
+                    )]);
                 }
-                emitted += considered;
-                annotated_minus_lines.push(annotated_minus_line);
-                annotated_plus_lines.push(annotated_plus_line);
-                emitted += 1;
-
-                // Greedy: move on to the next minus line.
-                continue 'minus_lines_loop;
+                self.annotated_minus_lines.push(
+                    annotated_minus_line
+                        .into_iter()
+                        .map(|(op, s, _span)| (op, s.to_string()))
+                        .collect(),
+                );
+                self.annotated_plus_lines.push(
+                    annotated_plus_line
+                        .into_iter()
+                        .map(|(op, s, _span)| (op, s.to_string()))
+                        .collect(),
+                );
+                self.emitted = candidate + 1;
+                self.considered = 0;
+                self.next_minus += 1;
             } else {
-                considered += 1;
+                self.considered += 1;
             }
         }
-        // No homolog was found for minus i; emit as unpaired.
-        annotated_minus_lines.push(vec![(noop_deletion, minus_line)]);
+        // Every minus line is resolved: any plus line seen from here on can only be an unpaired
+        // insertion, so commit it immediately rather than holding it back.
+        while self.emitted < self.plus_lines.len() {
+            self.annotated_plus_lines.push(vec![(
+                self.noop_insertion,
+                self.plus_lines[self.emitted].clone(),
+            )]);
+            self.emitted += 1;
+        }
     }
-    // Emit any remaining plus lines
-    for plus_line in &plus_lines[emitted..] {
-        annotated_plus_lines.push(vec![(noop_insertion, plus_line)]);
+}
+
+/// The granularity at which `infer_edits` tokenizes a line before aligning it against its
+/// homolog. The atomic units found here are what `align::Alignment` aligns against each other,
+/// so this determines how tightly intra-line highlights can be drawn.
+#[derive(Debug, Clone)]
+pub enum Tokenizer {
+    /// Split on a separator regex; delta's original behavior, tuned for source code.
+    /// `Tokenizer::default()` reproduces the long-standing built-in separator set.
+    Regex(Regex),
+    /// Align individual Unicode grapheme clusters (via `unicode_segmentation`, matching the
+    /// grapheme counting already used in `distance_contribution`). Suits CJK text and other
+    /// scripts the regex splitter under-segments.
+    Grapheme,
+    /// Align UAX#29 words. Suits prose, where the regex splitter's code-oriented separator set
+    /// over-segments.
+    Word,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Tokenizer::Regex(Regex::new(r"[ ,;.:()\[\]<>]+").unwrap())
     }
+}
 
-    (annotated_minus_lines, annotated_plus_lines)
+impl Tokenizer {
+    fn split<'a>(&self, line: &'a str) -> Vec<&'a str> {
+        match self {
+            Tokenizer::Regex(separators) => split_on_regex(line, separators),
+            Tokenizer::Grapheme => line.graphemes(true).collect(),
+            Tokenizer::Word => line.split_word_bounds().collect(),
+        }
+    }
 }
 
-/// Split line into tokens for alignment. The alignment algorithm aligns sequences of substrings;
-/// not individual characters.
-fn tokenize(line: &str) -> Vec<&str> {
-    let separators = Regex::new(r"[ ,;.:()\[\]<>]+").unwrap();
+fn split_on_regex<'a>(line: &'a str, separators: &Regex) -> Vec<&'a str> {
     let mut tokens = Vec::new();
     let mut offset = 0;
     for m in separators.find_iter(line) {
@@ -88,9 +452,20 @@ fn tokenize(line: &str) -> Vec<&str> {
     tokens
 }
 
+/// The location of an annotated section within the line it was taken from: `bytes` is a byte
+/// offset range directly usable to slice the line, and `columns` is the same extent measured in
+/// grapheme count, for consumers (e.g. LSP-style editors) that address text by column rather
+/// than byte. Both are cumulative over the line, so consecutive sections' ranges abut.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub bytes: Range<usize>,
+    pub columns: Range<usize>,
+}
+
 /// Use alignment to "annotate" minus and plus lines. An "annotated" line is a sequence of
 /// (s: &str, a: Annotation) pairs, where the &strs reference the memory
 /// of the original line and their concatenation equals the line.
+/// Each section is returned together with its `Span` within the line.
 fn annotate<'a, Annotation>(
     alignment: align::Alignment<'a>,
     noop_deletion: Annotation,
@@ -99,7 +474,11 @@ fn annotate<'a, Annotation>(
     insertion: Annotation,
     minus_line: &'a str,
     plus_line: &'a str,
-) -> (Vec<(Annotation, &'a str)>, Vec<(Annotation, &'a str)>, f64)
+) -> (
+    Vec<(Annotation, &'a str, Span)>,
+    Vec<(Annotation, &'a str, Span)>,
+    f64,
+)
 where
     Annotation: Copy,
 {
@@ -107,7 +486,8 @@ where
     let mut annotated_plus_line = Vec::new();
 
     let (mut x_offset, mut y_offset) = (0, 0);
-    let (mut minus_line_offset, mut plus_line_offset) = (0, 0);
+    let (mut minus_line_offset, mut minus_column_offset) = (0, 0);
+    let (mut plus_line_offset, mut plus_column_offset) = (0, 0);
     let (mut d_numer, mut d_denom) = (0, 0);
 
     // Note that the inputs to align::Alignment are not the original strings themselves, but
@@ -115,25 +495,34 @@ where
     // run_length_encoding to "coalesce" runs of the same edit operation into a single
     // operation. We now need to form a &str, pointing into the memory of the original line,
     // identifying a "section" which is the concatenation of the substrings involved in this
-    // coalesced operation. That's what the following closures do. Note that they must be called
-    // once only since they advance offset pointers.
+    // coalesced operation, together with its Span. That's what the following closures do. Note
+    // that they must be called once only since they advance offset pointers.
     let get_section = |n: usize,
                        line_offset: &mut usize,
+                       column_offset: &mut usize,
                        substrings_offset: &mut usize,
                        substrings: &[&str],
                        line: &'a str| {
         let section_length = substrings[*substrings_offset..*substrings_offset + n]
             .iter()
             .fold(0, |n, s| n + s.len());
-        let old_offset = *line_offset;
+        let byte_start = *line_offset;
         *line_offset += section_length;
         *substrings_offset += n;
-        &line[old_offset..*line_offset]
+        let section = &line[byte_start..*line_offset];
+        let column_start = *column_offset;
+        *column_offset += section.graphemes(true).count();
+        let span = Span {
+            bytes: byte_start..*line_offset,
+            columns: column_start..*column_offset,
+        };
+        (section, span)
     };
     let mut minus_section = |n| {
         get_section(
             n,
             &mut minus_line_offset,
+            &mut minus_column_offset,
             &mut x_offset,
             &alignment.x,
             minus_line,
@@ -143,6 +532,7 @@ where
         get_section(
             n,
             &mut plus_line_offset,
+            &mut plus_column_offset,
             &mut y_offset,
             &alignment.y,
             plus_line,
@@ -153,33 +543,35 @@ where
     for (op, n) in alignment.coalesced_operations() {
         match op {
             align::Operation::Deletion => {
-                let minus_section = minus_section(n);
+                let (minus_section, minus_span) = minus_section(n);
                 let n_d = distance_contribution(minus_section);
                 d_denom += n_d;
                 d_numer += n_d;
-                annotated_minus_line.push((deletion, minus_section));
+                annotated_minus_line.push((deletion, minus_section, minus_span));
             }
             align::Operation::NoOp => {
-                let minus_section = minus_section(n);
+                let (minus_section, minus_span) = minus_section(n);
                 let n_d = distance_contribution(minus_section);
                 d_denom += n_d; // TODO 2x ?
-                annotated_minus_line.push((noop_deletion, minus_section));
-                annotated_plus_line.push((noop_insertion, plus_section(n)));
+                annotated_minus_line.push((noop_deletion, minus_section, minus_span));
+                let (plus_section, plus_span) = plus_section(n);
+                annotated_plus_line.push((noop_insertion, plus_section, plus_span));
             }
             align::Operation::Substitution => {
-                let minus_section = minus_section(n);
+                let (minus_section, minus_span) = minus_section(n);
                 let n_d = distance_contribution(minus_section);
                 d_denom += n_d; // TODO 2x ?
                 d_numer += n_d; // TODO 2x?
-                annotated_minus_line.push((deletion, minus_section));
-                annotated_plus_line.push((insertion, plus_section(n)));
+                annotated_minus_line.push((deletion, minus_section, minus_span));
+                let (plus_section, plus_span) = plus_section(n);
+                annotated_plus_line.push((insertion, plus_section, plus_span));
             }
             align::Operation::Insertion => {
-                let plus_section = plus_section(n);
+                let (plus_section, plus_span) = plus_section(n);
                 let n_d = distance_contribution(plus_section);
                 d_denom += n_d;
                 d_numer += n_d;
-                annotated_plus_line.push((insertion, plus_section));
+                annotated_plus_line.push((insertion, plus_section, plus_span));
             }
         }
     }
@@ -207,6 +599,12 @@ mod tests {
 
     use EditOperation::*;
 
+    // Test-only shorthand for the default (regex) tokenizer, kept so the `tokenize(...)` call
+    // sites below didn't all need rewriting to `Tokenizer::default().split(...)`.
+    fn tokenize(line: &str) -> Vec<&str> {
+        Tokenizer::default().split(line)
+    }
+
     #[test]
     fn test_tokenize_1() {
         assert_eq!(tokenize("aaa bbb"), vec!["aaa", " ", "bbb"])
@@ -425,6 +823,312 @@ mod tests {
         )
     }
 
+    // Regression test for the scenario `align_lines` exists to fix: a minus line has a merely
+    // acceptable homolog early among the plus lines, and a much better one a little later. A
+    // greedy algorithm that locks onto the first candidate under `max_line_distance` would pair
+    // "aaaa wxyz cccc" with the mediocre "aaaa mxyz cccc" and leave its exact match stranded as
+    // an unpaired insertion; the optimal alignment must instead skip ahead to the exact match.
+    #[test]
+    fn test_infer_edits_8() {
+        assert_edits(
+            vec!["aaaa wxyz cccc", "dddd eeee ffff", "gggg hhhh iiii"],
+            vec![
+                "qqqq rrrr ssss",
+                "aaaa mxyz cccc",
+                "aaaa wxyz cccc",
+                "tttt uuuu vvvv",
+            ],
+            (
+                vec![
+                    vec![(MinusNoop, "aaaa wxyz cccc")],
+                    vec![(MinusNoop, "dddd eeee ffff")],
+                    vec![(MinusNoop, "gggg hhhh iiii")],
+                ],
+                vec![
+                    vec![(PlusNoop, "qqqq rrrr ssss")],
+                    vec![(PlusNoop, "aaaa mxyz cccc")],
+                    vec![(PlusNoop, "aaaa wxyz cccc")],
+                    vec![(PlusNoop, "tttt uuuu vvvv")],
+                ],
+            ),
+            0.5,
+        )
+    }
+
+    // `EditInferrer` resolves a minus line as soon as a pushed plus line is close enough, and
+    // defers lines it cannot yet decide (rather than ever revising a prior decision). Pushing the
+    // plus lines one at a time and checking the getters after each push exercises exactly that:
+    // a pairing, and the plus lines skipped over on the way to it, only become visible together,
+    // once the match that justifies skipping them has actually arrived.
+    #[test]
+    fn test_edit_inferrer_streaming() {
+        let minus_lines = vec![
+            "foo bar".to_string(),
+            "foo baz".to_string(),
+            "foo qux".to_string(),
+        ];
+        let mut inferrer = EditInferrer::new(
+            &minus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            0.4,
+            Tokenizer::default(),
+        );
+
+        inferrer.push_plus_line("foo zzz".to_string()); // rejected homolog candidate for "foo bar"
+        assert_eq!(inferrer.annotated_minus_lines().len(), 0);
+        assert_eq!(inferrer.annotated_plus_lines().len(), 0);
+
+        inferrer.push_plus_line("foo bar".to_string()); // exact match: resolves "foo bar"
+        assert_eq!(inferrer.annotated_minus_lines().len(), 1);
+        assert_eq!(inferrer.annotated_plus_lines().len(), 2);
+
+        inferrer.push_plus_line("nope".to_string()); // rejected homolog candidate for "foo baz"
+        assert_eq!(inferrer.annotated_minus_lines().len(), 1);
+        assert_eq!(inferrer.annotated_plus_lines().len(), 2);
+
+        inferrer.push_plus_line("foo baz".to_string()); // exact match: resolves "foo baz"
+        assert_eq!(inferrer.annotated_minus_lines().len(), 2);
+        assert_eq!(inferrer.annotated_plus_lines().len(), 4);
+
+        // "foo qux" never gets a plus line pushed for it; finalize resolves it as a deletion.
+        let (minus, plus) = inferrer.finalize();
+        assert_eq!(
+            minus,
+            vec![
+                vec![(MinusNoop, "foo bar".to_string())],
+                vec![(MinusNoop, "foo baz".to_string())],
+                vec![(MinusNoop, "foo qux".to_string())],
+            ]
+        );
+        assert_eq!(
+            plus,
+            vec![
+                vec![(PlusNoop, "foo zzz".to_string())],
+                vec![(PlusNoop, "foo bar".to_string())],
+                vec![(PlusNoop, "nope".to_string())],
+                vec![(PlusNoop, "foo baz".to_string())],
+            ]
+        );
+    }
+
+    // Reproduces the competing-candidates scenario that made a prior, full-recomputation-diffing
+    // implementation of `EditInferrer` panic: several plus lines are within `max_line_distance` of
+    // more than one minus line, so the set of plausible pairings churns a great deal as more plus
+    // lines arrive. Greedy pairing must not panic, and must still produce a coherent result once
+    // finalized.
+    #[test]
+    fn test_edit_inferrer_competing_candidates_does_not_panic() {
+        let minus_lines = vec![
+            "aaaa bbbb cccc".to_string(),
+            "aaaa cccc dddd".to_string(),
+            "eeee ffff gggg".to_string(),
+        ];
+        let mut inferrer = EditInferrer::new(
+            &minus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            0.66,
+            Tokenizer::default(),
+        );
+        for plus_line in [
+            "aaaa xxxx cccc",
+            "aaaa cccc yyyy",
+            "zzzz ffff gggg",
+            "aaaa bbbb cccc",
+        ] {
+            inferrer.push_plus_line(plus_line.to_string());
+        }
+        let (minus, plus) = inferrer.finalize();
+        assert_eq!(minus.len(), minus_lines.len());
+        assert_eq!(plus.len(), 4);
+    }
+
+    // `Tokenizer::Grapheme` aligns individual grapheme clusters rather than regex-separated runs,
+    // which matters for scripts (like CJK) the regex splitter doesn't segment at all: with the
+    // default tokenizer this line pair is a single token on each side, so the whole line is
+    // highlighted as one substitution, whereas the grapheme tokenizer leaves the common "你好"
+    // prefix unhighlighted.
+    #[test]
+    fn test_infer_edits_grapheme_tokenizer() {
+        let minus_lines = vec!["你好世界".to_string()];
+        let plus_lines = vec!["你好地球".to_string()];
+
+        let (regex_minus, regex_plus) = infer_edits(
+            &minus_lines,
+            &plus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            1.0,
+            &Tokenizer::default(),
+        );
+        assert_eq!(regex_minus, vec![vec![(Deletion, "你好世界")]]);
+        assert_eq!(regex_plus, vec![vec![(Insertion, "你好地球")]]);
+
+        let (grapheme_minus, grapheme_plus) = infer_edits(
+            &minus_lines,
+            &plus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            1.0,
+            &Tokenizer::Grapheme,
+        );
+        assert_eq!(
+            grapheme_minus,
+            vec![vec![(MinusNoop, "你好"), (Deletion, "世界")]]
+        );
+        assert_eq!(
+            grapheme_plus,
+            vec![vec![(PlusNoop, "你好"), (Insertion, "地球")]]
+        );
+    }
+
+    // `Tokenizer::Word` aligns UAX#29 words rather than regex-separated runs, which matters for
+    // prose where the regex splitter's code-oriented separator set over-segments: it splits "."
+    // out of "3.14" as its own token, so only "14" is highlighted, whereas the word tokenizer
+    // keeps "3.14" together as a single unit.
+    #[test]
+    fn test_infer_edits_word_tokenizer() {
+        let minus_lines = vec!["version 3.14 here".to_string()];
+        let plus_lines = vec!["version 3.15 here".to_string()];
+
+        let (regex_minus, regex_plus) = infer_edits(
+            &minus_lines,
+            &plus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            1.0,
+            &Tokenizer::default(),
+        );
+        assert_eq!(
+            regex_minus,
+            vec![vec![
+                (MinusNoop, "version 3."),
+                (Deletion, "14"),
+                (MinusNoop, " here")
+            ]]
+        );
+        assert_eq!(
+            regex_plus,
+            vec![vec![
+                (PlusNoop, "version 3."),
+                (Insertion, "15"),
+                (PlusNoop, " here")
+            ]]
+        );
+
+        let (word_minus, word_plus) = infer_edits(
+            &minus_lines,
+            &plus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            1.0,
+            &Tokenizer::Word,
+        );
+        assert_eq!(
+            word_minus,
+            vec![vec![
+                (MinusNoop, "version "),
+                (Deletion, "3.14"),
+                (MinusNoop, " here")
+            ]]
+        );
+        assert_eq!(
+            word_plus,
+            vec![vec![
+                (PlusNoop, "version "),
+                (Insertion, "3.15"),
+                (PlusNoop, " here")
+            ]]
+        );
+    }
+
+    // `Span` must let a caller recover each section directly from the original line: `bytes`
+    // slices straight back to the section text, `columns` has the same length as the section's
+    // grapheme count, and consecutive sections abut in both coordinate spaces. Multi-byte lines
+    // (reusing the "áaa"/"ááb" fixture from `test_infer_edits_2`, where byte and grapheme counts
+    // diverge) are where a byte/column mix-up would actually show up.
+    #[test]
+    fn test_span_multibyte() {
+        let minus_lines = vec!["áaa".to_string()];
+        let plus_lines = vec!["ááb".to_string()];
+        let (minus, plus) = infer_edits_with_spans(
+            &minus_lines,
+            &plus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            1.0,
+            &Tokenizer::Grapheme,
+        );
+        assert_spans_valid(&minus_lines[0], &minus[0]);
+        assert_spans_valid(&plus_lines[0], &plus[0]);
+        // "á" is 2 bytes but 1 grapheme/column: the byte and column spans must disagree here.
+        assert!(minus[0]
+            .iter()
+            .any(|(_, _, span)| span.bytes.len() != span.columns.len()));
+    }
+
+    #[test]
+    fn test_span_whole_line_deletion_and_insertion() {
+        let minus_lines = vec!["áaa".to_string()];
+        let plus_lines = vec!["completely different".to_string()];
+        let (minus, plus) = infer_edits_with_spans(
+            &minus_lines,
+            &plus_lines,
+            MinusNoop,
+            Deletion,
+            PlusNoop,
+            Insertion,
+            0.0,
+            &Tokenizer::default(),
+        );
+        assert_spans_valid(&minus_lines[0], &minus[0]);
+        assert_spans_valid(&plus_lines[0], &plus[0]);
+        assert_eq!(
+            minus[0],
+            vec![(
+                MinusNoop,
+                "áaa",
+                Span {
+                    bytes: 0..4,
+                    columns: 0..3
+                }
+            )]
+        );
+    }
+
+    // Checks the invariants `Span` promises for a line's full sequence of annotated sections:
+    // each section's `bytes` slices the line back to that exact section, `columns` has the same
+    // length as the section's grapheme count, and consecutive sections abut (no gap, no overlap)
+    // in both coordinate spaces.
+    fn assert_spans_valid<Annotation>(line: &str, sections: &[(Annotation, &str, Span)]) {
+        let (mut byte_cursor, mut column_cursor) = (0, 0);
+        for (_, section, span) in sections {
+            assert_eq!(span.bytes.start, byte_cursor);
+            assert_eq!(span.columns.start, column_cursor);
+            assert_eq!(&line[span.bytes.clone()], *section);
+            assert_eq!(span.columns.len(), section.graphemes(true).count());
+            byte_cursor = span.bytes.end;
+            column_cursor = span.columns.end;
+        }
+        assert_eq!(byte_cursor, line.len());
+        assert_eq!(column_cursor, line.graphemes(true).count());
+    }
+
     fn assert_edits(
         minus_lines: Vec<&str>,
         plus_lines: Vec<&str>,
@@ -447,6 +1151,7 @@ mod tests {
             PlusNoop,
             Insertion,
             max_line_distance,
+            &Tokenizer::default(),
         );
         assert_eq!(actual_edits, expected_edits);
     }
@@ -553,5 +1258,4 @@ mod tests {
     fn is_edit(edit: &EditOperation) -> bool {
         *edit == Deletion || *edit == Insertion
     }
-
-}
\ No newline at end of file
+}